@@ -2,12 +2,33 @@ use anyhow::Result;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{Sample, Stream};
 use device_query::{DeviceQuery, DeviceState, Keycode};
+use midir::{MidiInput, MidiInputConnection};
 use ringbuf::traits::Split;
 use ringbuf::{traits::*, HeapRb};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+// MIDI CC64 (서스테인 페달) 번호
+const MIDI_CC_SUSTAIN: u8 = 64;
+// 피치 벤드 휠이 끝까지 꺾였을 때의 변화 폭 (센트)
+const PITCH_BEND_RANGE_CENTS: f32 = 200.0;
+
+// 키보드/MIDI 입력을 음원 엔진으로 전달하는 공용 이벤트
+#[derive(Debug, Clone, Copy)]
+enum SynthEvent {
+    NoteOn { note_number: u8, velocity: u8 },
+    NoteOff { note_number: u8 },
+    PitchBend { cents: f32 },
+    Sustain { on: bool },
+}
+
+// MIDI 노트 번호를 주파수로 변환 (A4 = 69 = 440Hz)
+fn midi_note_to_frequency(note_number: u8) -> f32 {
+    440.0 * 2f32.powf((note_number as f32 - 69.0) / 12.0)
+}
+
 // 오실리에이터 유형
 #[derive(Debug, Clone, Copy)]
 enum Oscillator {
@@ -17,6 +38,28 @@ enum Oscillator {
     Triangle,
 }
 
+// 노트 전체에 적용되는 고정 디튠 (유니즌 스프레드와는 별개로 전체를 한쪽으로 밀어주는 용도).
+// 런타임 컨트롤은 따로 없는 컴파일 타임 상수이며, 값을 바꾸려면 이 상수 자체를 수정한다.
+const NOTE_COARSE_DETUNE_CENTS: f32 = -7.0;
+const NOTE_FINE_DETUNE_CENTS: f32 = 3.0;
+
+// 한 노트를 두껍게 만드는 유니즌 보이스 개수와, 그 보이스들을 펼칠 디튠 폭
+const UNISON_VOICE_COUNT: usize = 3;
+const UNISON_DETUNE_CENTS: f32 = 8.0;
+
+// 유니즌 보이스 i의 디튠(센트)을 ±spread_cents 범위에서 대칭으로 계산한다
+fn unison_offsets_cents(voice_count: usize, spread_cents: f32) -> Vec<f32> {
+    if voice_count <= 1 {
+        return vec![0.0];
+    }
+    (0..voice_count)
+        .map(|i| {
+            let t = i as f32 / (voice_count - 1) as f32; // 0.0 ~ 1.0
+            -spread_cents + 2.0 * spread_cents * t
+        })
+        .collect()
+}
+
 // 노트 정보를 저장할 구조체
 #[derive(Debug, Clone)]
 struct Note {
@@ -24,6 +67,7 @@ struct Note {
     is_playing: bool,
     oscillator: Oscillator,
     amplitude: f32,
+    detune_cents: f32,
 }
 
 impl Note {
@@ -33,77 +77,399 @@ impl Note {
             is_playing: false,
             oscillator,
             amplitude: 0.0,
+            detune_cents: NOTE_COARSE_DETUNE_CENTS + NOTE_FINE_DETUNE_CENTS,
         }
     }
 
-    // 주파수에 따른 샘플 생성
-    fn generate_sample(&mut self, phase: &mut f32, sample_rate: f32) -> f32 {
-        if !self.is_playing {
-            return 0.0;
-        }
-
-        // 위상 증가
-        *phase += self.frequency / sample_rate;
-        if *phase >= 1.0 {
-            *phase -= 1.0;
-        }
-
-        // 오실리에이터 유형에 따른 파형 생성
-        let sample = match self.oscillator {
-            Oscillator::Sine => (2.0 * std::f32::consts::PI * *phase).sin(),
+    // 주어진 위상에서 현재 오실레이터 유형의 파형 값을 계산한다 (-1.0~1.0)
+    fn waveform_at(&self, phase: f32) -> f32 {
+        match self.oscillator {
+            Oscillator::Sine => (2.0 * std::f32::consts::PI * phase).sin(),
             Oscillator::Square => {
-                if *phase < 0.5 {
+                if phase < 0.5 {
                     1.0
                 } else {
                     -1.0
                 }
             }
-            Oscillator::Sawtooth => 2.0 * *phase - 1.0,
+            Oscillator::Sawtooth => 2.0 * phase - 1.0,
             Oscillator::Triangle => {
-                if *phase < 0.5 {
-                    4.0 * *phase - 1.0
+                if phase < 0.5 {
+                    4.0 * phase - 1.0
                 } else {
-                    3.0 - 4.0 * *phase
+                    3.0 - 4.0 * phase
                 }
             }
+        }
+    }
+
+    // 주파수에 따른 샘플 생성 (유니즌 없이 단일 보이스)
+    fn generate_sample(&mut self, phase: &mut f32, sample_rate: f32) -> f32 {
+        if !self.is_playing {
+            return 0.0;
+        }
+
+        let frequency = self.frequency * 2f32.powf(self.detune_cents / 1200.0);
+        *phase += frequency / sample_rate;
+        if *phase >= 1.0 {
+            *phase -= 1.0;
+        }
+
+        self.waveform_at(*phase) * self.amplitude
+    }
+
+    // 유니즌 보이스들을 합산해 생성. phases 의 길이가 보이스 개수를 결정한다
+    fn generate_unison_sample(&mut self, phases: &mut [f32], sample_rate: f32) -> f32 {
+        if !self.is_playing || phases.is_empty() {
+            return 0.0;
+        }
+
+        let base_frequency = self.frequency * 2f32.powf(self.detune_cents / 1200.0);
+        let offsets_cents = unison_offsets_cents(phases.len(), UNISON_DETUNE_CENTS);
+
+        let mut sum = 0.0;
+        for (phase, offset_cents) in phases.iter_mut().zip(offsets_cents.iter()) {
+            let frequency = base_frequency * 2f32.powf(offset_cents / 1200.0);
+            *phase += frequency / sample_rate;
+            if *phase >= 1.0 {
+                *phase -= 1.0;
+            }
+            sum += self.waveform_at(*phase);
+        }
+
+        // 보이스 개수로 나누어 클리핑을 방지한다
+        (sum / phases.len() as f32) * self.amplitude
+    }
+}
+
+// FM 오퍼레이터 (YM2612 스타일 4-오퍼레이터 FM 합성의 기본 단위)
+#[derive(Debug, Clone)]
+struct FmOperator {
+    phase: f32,
+    multiple: f32,
+    total_level: f32,
+    envelope: Envelope,
+    last_output: f32,
+    prev_output: f32,
+}
+
+impl FmOperator {
+    // multiple 0 은 주파수 배율 0.5 를 의미한다 (YM2612 규약)
+    fn new(multiple: u8, total_level: f32, envelope: Envelope) -> Self {
+        let multiple = if multiple == 0 { 0.5 } else { multiple as f32 };
+        Self {
+            phase: 0.0,
+            multiple,
+            total_level,
+            envelope,
+            last_output: 0.0,
+            prev_output: 0.0,
+        }
+    }
+
+    // modulation: 이 오퍼레이터로 들어오는 다른 오퍼레이터들의 변조량 (위상에 더해짐)
+    fn tick(&mut self, base_frequency: f32, modulation: f32, sample_rate: f32) -> f32 {
+        let frequency = base_frequency * self.multiple;
+        self.phase += frequency / sample_rate;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+
+        let env_value = self.envelope.process();
+        let output = (2.0 * std::f32::consts::PI * (self.phase + modulation)).sin() * self.total_level;
+        let enveloped_output = output * env_value;
+
+        // 피드백은 직전 출력을 다시 변조에 쓰므로, 엔벨로프가 적용된 값을 저장해야
+        // 디케이/릴리즈 구간에서도 다른 변조 경로와 같은 음량으로 따라간다
+        self.prev_output = self.last_output;
+        self.last_output = enveloped_output;
+
+        enveloped_output
+    }
+
+    // 피드백용으로 직전 두 샘플을 평균내어 에일리어싱을 줄인다
+    fn feedback_output(&self) -> f32 {
+        (self.last_output + self.prev_output) * 0.5
+    }
+}
+
+// 4-오퍼레이터 FM 알고리즘 (0~7), 각 알고리즘이 어떤 오퍼레이터를 캐리어로 쓰는지 정의
+#[derive(Debug, Clone, Copy)]
+struct FmAlgorithm {
+    carriers: [bool; 4],
+}
+
+const FM_ALGORITHMS: [FmAlgorithm; 8] = [
+    // 0: 직렬 체인 op1 -> op2 -> op3 -> op4, op4만 캐리어
+    FmAlgorithm {
+        carriers: [false, false, false, true],
+    },
+    // 1: (op1 + op2) -> op3 -> op4
+    FmAlgorithm {
+        carriers: [false, false, false, true],
+    },
+    // 2: op1 -> op3, op2 -> op3 -> op4 (병렬 합류)
+    FmAlgorithm {
+        carriers: [false, false, false, true],
+    },
+    // 3: op1 -> op2 -> op4, op3 -> op4
+    FmAlgorithm {
+        carriers: [false, false, false, true],
+    },
+    // 4: (op1 -> op2), (op3 -> op4) 두 개의 독립적인 캐리어
+    FmAlgorithm {
+        carriers: [false, true, false, true],
+    },
+    // 5: op1 이 op2, op3, op4 모두를 변조, 세 캐리어
+    FmAlgorithm {
+        carriers: [false, true, true, true],
+    },
+    // 6: op1 -> op2, op3과 op4는 각각 캐리어
+    FmAlgorithm {
+        carriers: [false, true, true, true],
+    },
+    // 7: 네 오퍼레이터 모두 병렬 캐리어 (변조 없음)
+    FmAlgorithm {
+        carriers: [true, true, true, true],
+    },
+];
+
+// FM 재생 모드 설정: 어떤 알고리즘과 피드백 강도로 FmVoice 를 생성할지 지정한다
+#[derive(Debug, Clone, Copy)]
+struct FmConfig {
+    algorithm: usize,
+    feedback_strength: u8,
+}
+
+// 4-오퍼레이터 FM 신스 보이스
+#[derive(Debug, Clone)]
+struct FmVoice {
+    operators: [FmOperator; 4],
+    algorithm: usize,
+    feedback_strength: u8, // 0~7
+    base_frequency: f32,
+    is_playing: bool,
+}
+
+impl FmVoice {
+    fn new(base_frequency: f32, operators: [FmOperator; 4], algorithm: usize, feedback_strength: u8) -> Self {
+        Self {
+            operators,
+            algorithm: algorithm.min(7),
+            feedback_strength: feedback_strength.min(7),
+            base_frequency,
+            is_playing: false,
+        }
+    }
+
+    // 합리적인 기본 오퍼레이터 스택(모두 1배음, 단계적으로 줄어드는 레벨)으로 FM 보이스를 구성한다
+    fn with_default_operators(
+        base_frequency: f32,
+        algorithm: usize,
+        feedback_strength: u8,
+        sample_rate: f32,
+    ) -> Self {
+        let operators = [
+            FmOperator::new(1, 1.0, Envelope::new(0.01, 0.1, -3.0, 0.2, sample_rate)),
+            FmOperator::new(1, 0.8, Envelope::new(0.01, 0.15, -6.0, 0.2, sample_rate)),
+            FmOperator::new(1, 0.6, Envelope::new(0.01, 0.2, -9.0, 0.2, sample_rate)),
+            FmOperator::new(1, 0.4, Envelope::new(0.01, 0.25, -12.0, 0.2, sample_rate)),
+        ];
+        Self::new(base_frequency, operators, algorithm, feedback_strength)
+    }
+
+    fn trigger(&mut self) {
+        self.is_playing = true;
+        for op in self.operators.iter_mut() {
+            op.envelope.trigger();
+        }
+    }
+
+    fn release(&mut self) {
+        for op in self.operators.iter_mut() {
+            op.envelope.release();
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.operators
+            .iter()
+            .all(|op| matches!(op.envelope.phase, EnvelopePhase::Idle))
+    }
+
+    // 알고리즘에 따른 오퍼레이터 라우팅을 적용해 한 샘플을 생성한다.
+    // frequency_multiplier 는 피치 벤드/비브라토를 이 샘플에만 일시적으로 반영하기 위한 배율이다
+    fn generate_sample(&mut self, frequency_multiplier: f32, sample_rate: f32) -> f32 {
+        if !self.is_playing {
+            return 0.0;
+        }
+
+        if self.is_finished() {
+            self.is_playing = false;
+            return 0.0;
+        }
+
+        // 피드백 스케일: 0~7 단계를 대략 0~2π 범위의 변조 깊이로 매핑
+        let feedback_scale = self.feedback_strength as f32 / 7.0 * std::f32::consts::PI * 2.0;
+        let feedback_modulation = self.operators[0].feedback_output() * feedback_scale;
+
+        let algorithm = FM_ALGORITHMS[self.algorithm];
+        let base_frequency = self.base_frequency * frequency_multiplier;
+
+        // 각 알고리즘의 변조 라우팅에 따라 오퍼레이터를 순서대로 처리한다.
+        // op2 는 op1 이 직접 변조하는 알고리즘(0, 3, 4, 5, 6)에서만 out1 을 받는다
+        let out1 = self.operators[0].tick(base_frequency, feedback_modulation, sample_rate);
+        let out2 = match self.algorithm {
+            0 | 3 | 4 | 5 | 6 => self.operators[1].tick(base_frequency, out1, sample_rate),
+            _ => self.operators[1].tick(base_frequency, 0.0, sample_rate),
+        };
+        let out3 = match self.algorithm {
+            0 => self.operators[2].tick(base_frequency, out2, sample_rate),
+            1 | 2 => self.operators[2].tick(base_frequency, out1 + out2, sample_rate),
+            5 => self.operators[2].tick(base_frequency, out1, sample_rate),
+            _ => self.operators[2].tick(base_frequency, 0.0, sample_rate),
         };
-        sample * self.amplitude
+        let out4 = match self.algorithm {
+            0 | 1 | 2 | 4 => self.operators[3].tick(base_frequency, out3, sample_rate),
+            3 => self.operators[3].tick(base_frequency, out2 + out3, sample_rate),
+            5 => self.operators[3].tick(base_frequency, out1, sample_rate),
+            _ => self.operators[3].tick(base_frequency, 0.0, sample_rate),
+        };
+
+        let outputs = [out1, out2, out3, out4];
+
+        // 캐리어로 지정된 오퍼레이터들의 출력만 합산
+        algorithm
+            .carriers
+            .iter()
+            .zip(outputs.iter())
+            .filter(|(is_carrier, _)| **is_carrier)
+            .map(|(_, value)| *value)
+            .sum()
     }
 }
 
-// 키보드 키와 주파수 매핑
-fn create_key_frequency_map() -> HashMap<Keycode, f32> {
+// 키보드 키와 MIDI 노트 번호 매핑 (C4 = 60)
+fn create_key_note_map() -> HashMap<Keycode, u8> {
     let mut map = HashMap::new();
 
-    map.insert(Keycode::Z, 261.63); // C4
-    map.insert(Keycode::S, 277.18); // C#4
-    map.insert(Keycode::X, 293.66); // D4
-    map.insert(Keycode::D, 311.13); // D#4
-    map.insert(Keycode::C, 329.63); // E4
-    map.insert(Keycode::V, 349.23); // F4
-    map.insert(Keycode::G, 369.99); // F#4
-    map.insert(Keycode::B, 392.99); // G4
-    map.insert(Keycode::H, 415.30); // G#4
-    map.insert(Keycode::N, 440.00); // A4
-    map.insert(Keycode::J, 466.16); // A#4
-    map.insert(Keycode::M, 493.88); // B4
-    map.insert(Keycode::Comma, 523.25); // C5
+    map.insert(Keycode::Z, 60); // C4
+    map.insert(Keycode::S, 61); // C#4
+    map.insert(Keycode::X, 62); // D4
+    map.insert(Keycode::D, 63); // D#4
+    map.insert(Keycode::C, 64); // E4
+    map.insert(Keycode::V, 65); // F4
+    map.insert(Keycode::G, 66); // F#4
+    map.insert(Keycode::B, 67); // G4
+    map.insert(Keycode::H, 68); // G#4
+    map.insert(Keycode::N, 69); // A4
+    map.insert(Keycode::J, 70); // A#4
+    map.insert(Keycode::M, 71); // B4
+    map.insert(Keycode::Comma, 72); // C5
 
     map
 }
 
-// ADSR 엔벨로프
+// 고정 속도의 키보드 입력 기본 벨로시티
+const KEYBOARD_VELOCITY: u8 = 100;
+
+// 들어온 원시 MIDI 바이트열을 SynthEvent 로 해석한다
+fn parse_midi_message(message: &[u8]) -> Option<SynthEvent> {
+    let status = *message.first()?;
+    let kind = status & 0xF0;
+
+    match kind {
+        0x90 => {
+            // Note On (velocity 0 은 관례적으로 Note Off 로 취급)
+            let note_number = *message.get(1)?;
+            let velocity = *message.get(2)?;
+            if velocity == 0 {
+                Some(SynthEvent::NoteOff { note_number })
+            } else {
+                Some(SynthEvent::NoteOn { note_number, velocity })
+            }
+        }
+        0x80 => {
+            let note_number = *message.get(1)?;
+            Some(SynthEvent::NoteOff { note_number })
+        }
+        0xB0 => {
+            let controller = *message.get(1)?;
+            let value = *message.get(2)?;
+            if controller == MIDI_CC_SUSTAIN {
+                Some(SynthEvent::Sustain { on: value >= 64 })
+            } else {
+                None
+            }
+        }
+        0xE0 => {
+            let lsb = *message.get(1)? as u16;
+            let msb = *message.get(2)? as u16;
+            let bend14 = (msb << 7) | lsb; // 0..16383, 중심값 8192
+            let normalized = (bend14 as f32 - 8192.0) / 8192.0;
+            Some(SynthEvent::PitchBend {
+                cents: normalized * PITCH_BEND_RANGE_CENTS,
+            })
+        }
+        _ => None,
+    }
+}
+
+// 사용 가능한 첫 MIDI 입력 포트를 열어 들어오는 메시지를 이벤트 큐로 전달한다
+fn open_midi_input(
+    producer: Arc<Mutex<impl Producer<Item = SynthEvent> + Send + 'static>>,
+) -> Result<Option<MidiInputConnection<()>>> {
+    let midi_in = MidiInput::new("dingdangdong-input")?;
+    let ports = midi_in.ports();
+    let Some(port) = ports.first() else {
+        println!("No MIDI input device found, keyboard input only");
+        return Ok(None);
+    };
+    let port_name = midi_in.port_name(port)?;
+
+    let connection = midi_in
+        .connect(
+            port,
+            "dingdangdong-input-port",
+            move |_timestamp, message, _| {
+                if let Some(event) = parse_midi_message(message) {
+                    if let Ok(mut producer) = producer.lock() {
+                        let _ = producer.try_push(event);
+                    }
+                }
+            },
+            (),
+        )
+        .map_err(|err| anyhow::anyhow!("Failed to connect to MIDI input: {}", err))?;
+
+    println!("Listening for MIDI input on: {}", port_name);
+    Ok(Some(connection))
+}
+
+// 데시벨을 선형 게인으로 변환 (sustain_level 을 dB 단위로 받기 위함)
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+// 목표값에 도달했다고 간주할 임계값. 지수 곡선은 수학적으로 목표에 무한히 접근만 하므로
+// 이 값 안으로 들어오면 다음 단계로 전이시킨다
+const ENVELOPE_TARGET_THRESHOLD: f32 = 0.001;
+// 어택 구간이 살짝 오버슈트하도록 잡는 목표값 (아날로그 엔벨로프의 특징적인 느낌)
+const ATTACK_OVERSHOOT_TARGET: f32 = 1.05;
+
+// ADSR 엔벨로프. 디케이/릴리즈는 RC 회로처럼 목표값에 지수적으로 접근한다
+#[derive(Debug, Clone)]
 struct Envelope {
     attack_time: f32,
     decay_time: f32,
-    sustain_level: f32,
+    sustain_level: f32, // 선형 게인 (dB 에서 변환됨)
     release_time: f32,
     current_level: f32,
     phase: EnvelopePhase,
     sample_rate: f32,
-    samples_processed: usize,
 }
 
+#[derive(Debug, Clone)]
 enum EnvelopePhase {
     Idle,
     Attack,
@@ -113,108 +479,588 @@ enum EnvelopePhase {
 }
 
 impl Envelope {
+    // sustain_level_db 는 dB 단위 (예: -12.0)
     fn new(
         attack_time: f32,
         decay_time: f32,
-        sustain_level: f32,
+        sustain_level_db: f32,
         release_time: f32,
         sample_rate: f32,
     ) -> Self {
         Self {
             attack_time,
             decay_time,
-            sustain_level,
+            sustain_level: db_to_gain(sustain_level_db),
             release_time,
             current_level: 0.0,
             phase: EnvelopePhase::Idle,
             sample_rate,
-            samples_processed: 0,
         }
     }
 
     fn trigger(&mut self) {
         self.phase = EnvelopePhase::Attack;
-        self.samples_processed = 0;
     }
 
     fn release(&mut self) {
         self.phase = EnvelopePhase::Release;
-        self.samples_processed = 0;
+    }
+
+    // 계수 exp(-1 / (time_seconds * sample_rate)) 로 한 샘플만큼 target 쪽으로 지수적으로 이동시킨 값을 반환한다.
+    // time_seconds 가 0 이면 즉시 도달
+    fn approach(current: f32, target: f32, time_seconds: f32, sample_rate: f32) -> f32 {
+        if time_seconds <= 0.0 {
+            return target;
+        }
+        let coeff = (-1.0 / (time_seconds * sample_rate)).exp();
+        target + (current - target) * coeff
     }
 
     fn process(&mut self) -> f32 {
         match self.phase {
             EnvelopePhase::Idle => 0.0,
             EnvelopePhase::Attack => {
-                let attack_samples = (self.attack_time * self.sample_rate) as usize;
-                if attack_samples == 0 {
+                self.current_level = Self::approach(
+                    self.current_level,
+                    ATTACK_OVERSHOOT_TARGET,
+                    self.attack_time,
+                    self.sample_rate,
+                );
+                if (ATTACK_OVERSHOOT_TARGET - self.current_level).abs() <= ENVELOPE_TARGET_THRESHOLD {
                     self.current_level = 1.0;
                     self.phase = EnvelopePhase::Decay;
-                    self.samples_processed = 0;
-                } else {
-                    self.current_level = self.samples_processed as f32 / attack_samples as f32;
-                    if self.samples_processed >= attack_samples {
-                        self.phase = EnvelopePhase::Decay;
-                        self.samples_processed = 0;
-                    }
                 }
-                self.samples_processed += 1;
-                self.current_level
+                self.current_level.min(1.0)
             }
             EnvelopePhase::Decay => {
-                let decay_samples = (self.decay_time * self.sample_rate) as usize;
-                if decay_samples == 0 {
+                self.current_level = Self::approach(
+                    self.current_level,
+                    self.sustain_level,
+                    self.decay_time,
+                    self.sample_rate,
+                );
+                if (self.current_level - self.sustain_level).abs() <= ENVELOPE_TARGET_THRESHOLD {
                     self.current_level = self.sustain_level;
                     self.phase = EnvelopePhase::Sustain;
-                } else {
-                    self.current_level = 1.0
-                        - (1.0 - self.sustain_level)
-                            * (self.samples_processed as f32 / decay_samples as f32);
-                    if self.samples_processed >= decay_samples {
-                        self.phase = EnvelopePhase::Sustain;
-                    }
                 }
-                self.samples_processed += 1;
                 self.current_level
             }
             EnvelopePhase::Sustain => self.sustain_level,
             EnvelopePhase::Release => {
-                let release_samples = (self.release_time * self.sample_rate) as usize;
-                if release_samples == 0 {
+                self.current_level =
+                    Self::approach(self.current_level, 0.0, self.release_time, self.sample_rate);
+                if self.current_level <= ENVELOPE_TARGET_THRESHOLD {
                     self.current_level = 0.0;
                     self.phase = EnvelopePhase::Idle;
-                } else {
-                    self.current_level = self.sustain_level
-                        * (1.0 - self.samples_processed as f32 / release_samples as f32);
-                    if self.samples_processed >= release_samples {
-                        self.current_level = 0.0;
-                        self.phase = EnvelopePhase::Idle;
-                    }
                 }
-                self.samples_processed += 1;
                 self.current_level
             }
         }
     }
 }
 
+// 비브라토(피치)와 트레몰로(음량)에 쓰이는 공용 저주파 발진기
+struct Lfo {
+    phase: f32,
+    rate_hz: f32,
+    vibrato_depth_semitones: f32,
+    tremolo_depth: f32,
+    delay_seconds: f32,
+}
+
+impl Lfo {
+    fn new(rate_hz: f32, vibrato_depth_semitones: f32, tremolo_depth: f32, delay_seconds: f32) -> Self {
+        Self {
+            phase: 0.0,
+            rate_hz,
+            vibrato_depth_semitones,
+            tremolo_depth,
+            delay_seconds,
+        }
+    }
+
+    // -1.0~1.0 범위의 원시 사인값을 반환하고 내부 위상을 한 프레임만큼 전진시킨다
+    fn advance(&mut self, sample_rate: f32) -> f32 {
+        self.phase += self.rate_hz / sample_rate;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+        (2.0 * std::f32::consts::PI * self.phase).sin()
+    }
+}
+
+// 노트가 눌린 뒤 LFO 효과가 서서히 드러나게 하는 페이드인 계수 (0~1)
+const LFO_FADE_IN_SECONDS: f32 = 0.3;
+
+fn lfo_fade_in(samples_since_trigger: usize, delay_seconds: f32, sample_rate: f32) -> f32 {
+    let delay_samples = (delay_seconds * sample_rate) as usize;
+    if samples_since_trigger < delay_samples {
+        return 0.0;
+    }
+    let ramp_samples = ((LFO_FADE_IN_SECONDS * sample_rate) as usize).max(1);
+    let into_ramp = samples_since_trigger - delay_samples;
+    (into_ramp as f32 / ramp_samples as f32).min(1.0)
+}
+
+// SF2 사운드폰트 한 프리셋 안의 샘플 리전 (노트/벨로시티 범위에 대응하는 PCM 샘플 한 조각)
+#[derive(Debug, Clone)]
+struct SampleRegion {
+    samples: Arc<Vec<i16>>,
+    sample_rate: u32,
+    root_key: u8,
+    low_key: u8,
+    high_key: u8,
+    low_velocity: u8,
+    high_velocity: u8,
+    loop_start: usize,
+    loop_end: usize,
+}
+
+impl SampleRegion {
+    fn matches(&self, note_number: u8, velocity: u8) -> bool {
+        (self.low_key..=self.high_key).contains(&note_number)
+            && (self.low_velocity..=self.high_velocity).contains(&velocity)
+    }
+}
+
+// 로드된 사운드폰트의 한 프리셋 (악기 하나에 해당)
+#[derive(Debug, Clone)]
+struct SoundFontPreset {
+    name: String,
+    regions: Vec<SampleRegion>,
+}
+
+impl SoundFontPreset {
+    fn region_for(&self, note_number: u8, velocity: u8) -> Option<&SampleRegion> {
+        self.regions
+            .iter()
+            .find(|region| region.matches(note_number, velocity))
+    }
+}
+
+// 파싱된 .sf2 파일. 지금은 첫 프리셋만 사용한다
+#[derive(Debug, Clone)]
+struct SoundFont {
+    presets: Vec<SoundFontPreset>,
+}
+
+impl SoundFont {
+    // .sf2 파일을 읽어 프리셋과 샘플 리전들을 추출한다
+    fn load(path: &str) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let sound_font = sf2::SoundFont2::load(&mut std::io::BufReader::new(file))
+            .map_err(|err| anyhow::anyhow!("Failed to parse SoundFont {}: {:?}", path, err))?;
+
+        let presets = sound_font
+            .presets
+            .iter()
+            .map(|preset| SoundFontPreset {
+                name: preset.header.name.clone(),
+                regions: preset
+                    .zones
+                    .iter()
+                    .flat_map(|zone| sample_regions_from_preset_zone(&sound_font, zone))
+                    .collect(),
+            })
+            .collect();
+
+        Ok(Self { presets })
+    }
+
+    fn default_preset(&self) -> Option<&SoundFontPreset> {
+        self.presets.first()
+    }
+}
+
+// 프리셋 존은 SampleID 를 직접 들고 있지 않는다: Instrument 제너레이터로 별도의 악기를 가리키고,
+// 실제 SampleID 는 그 악기 자신의 존들에 있다. 프리셋 존의 키/벨로시티 범위는 악기 존에 범위가
+// 없을 때만 물려받는 기본값으로 쓰인다 (SF2 제너레이터 상속 규칙)
+fn sample_regions_from_preset_zone(sound_font: &sf2::SoundFont2, preset_zone: &sf2::Zone) -> Vec<SampleRegion> {
+    let instrument_id = preset_zone.gen_list.iter().find_map(|gen| match gen {
+        sf2::GeneratorType::Instrument(id) => Some(*id as usize),
+        _ => None,
+    });
+    let Some(instrument) = instrument_id.and_then(|id| sound_font.instruments.get(id)) else {
+        return Vec::new();
+    };
+
+    let preset_key_range = preset_zone.gen_list.iter().find_map(|gen| match gen {
+        sf2::GeneratorType::KeyRange(range) => Some((range.low, range.high)),
+        _ => None,
+    });
+    let preset_velocity_range = preset_zone.gen_list.iter().find_map(|gen| match gen {
+        sf2::GeneratorType::VelRange(range) => Some((range.low, range.high)),
+        _ => None,
+    });
+
+    instrument
+        .zones
+        .iter()
+        .filter_map(|instrument_zone| {
+            sample_region_from_instrument_zone(
+                sound_font,
+                instrument_zone,
+                preset_key_range,
+                preset_velocity_range,
+            )
+        })
+        .collect()
+}
+
+// 악기 존의 제너레이터들에서 샘플 ID를 읽어 SampleRegion 으로 변환한다.
+// 키/벨로시티 범위는 악기 존 자신의 값을 우선하고, 없으면 프리셋 존에서 물려받은 값을 쓴다
+fn sample_region_from_instrument_zone(
+    sound_font: &sf2::SoundFont2,
+    zone: &sf2::Zone,
+    preset_key_range: Option<(u8, u8)>,
+    preset_velocity_range: Option<(u8, u8)>,
+) -> Option<SampleRegion> {
+    let sample_id = zone.gen_list.iter().find_map(|gen| match gen {
+        sf2::GeneratorType::SampleID(id) => Some(*id as usize),
+        _ => None,
+    })?;
+    let header = sound_font.sample_headers.get(sample_id)?;
+
+    let key_range = zone
+        .gen_list
+        .iter()
+        .find_map(|gen| match gen {
+            sf2::GeneratorType::KeyRange(range) => Some((range.low, range.high)),
+            _ => None,
+        })
+        .or(preset_key_range);
+    let velocity_range = zone
+        .gen_list
+        .iter()
+        .find_map(|gen| match gen {
+            sf2::GeneratorType::VelRange(range) => Some((range.low, range.high)),
+            _ => None,
+        })
+        .or(preset_velocity_range);
+
+    let (low_key, high_key) = key_range.unwrap_or((0, 127));
+    let (low_velocity, high_velocity) = velocity_range.unwrap_or((0, 127));
+
+    let start = header.start as usize;
+    let end = header.end as usize;
+    let samples = sound_font.sample_data.get(start..end)?.to_vec();
+
+    Some(SampleRegion {
+        samples: Arc::new(samples),
+        sample_rate: header.sample_rate,
+        root_key: header.origpitch,
+        low_key,
+        high_key,
+        low_velocity,
+        high_velocity,
+        loop_start: header.startloop as usize - start,
+        loop_end: header.endloop as usize - start,
+    })
+}
+
+// 샘플 기반 재생을 담당하는 보이스. 오실레이터 대신 PCM 데이터를 재생한다
+#[derive(Debug, Clone)]
+struct SampleVoice {
+    region: Arc<SampleRegion>,
+    playback_position: f64,
+    playback_rate: f64,
+    envelope: Envelope,
+    releasing: bool,
+    is_playing: bool,
+}
+
+impl SampleVoice {
+    fn new(region: Arc<SampleRegion>, note_number: u8, output_sample_rate: f32, envelope: Envelope) -> Self {
+        let playback_rate = region.sample_rate as f64
+            * 2f64.powf((note_number as f64 - region.root_key as f64) / 12.0)
+            / output_sample_rate as f64;
+
+        Self {
+            region,
+            playback_position: 0.0,
+            playback_rate,
+            envelope,
+            releasing: false,
+            is_playing: true,
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.playback_position = 0.0;
+        self.releasing = false;
+        self.is_playing = true;
+        self.envelope.trigger();
+    }
+
+    fn release(&mut self) {
+        self.releasing = true;
+        self.envelope.release();
+    }
+
+    // 선형 보간으로 리샘플링하며, 노트가 눌려있는 동안은 루프 구간을 반복 재생한다
+    fn generate_sample(&mut self) -> f32 {
+        if !self.is_playing {
+            return 0.0;
+        }
+
+        let samples = &self.region.samples;
+        let index = self.playback_position.floor() as usize;
+        if index + 1 >= samples.len() {
+            self.is_playing = false;
+            return 0.0;
+        }
+
+        let fraction = (self.playback_position - index as f64) as f32;
+        let a = samples[index] as f32 / i16::MAX as f32;
+        let b = samples[index + 1] as f32 / i16::MAX as f32;
+        let sample = a + (b - a) * fraction;
+
+        self.playback_position += self.playback_rate;
+
+        if !self.releasing && self.region.loop_end > self.region.loop_start {
+            if self.playback_position as usize >= self.region.loop_end {
+                let loop_length = self.region.loop_end - self.region.loop_start;
+                self.playback_position -= loop_length as f64;
+            }
+        } else if self.playback_position as usize + 1 >= samples.len() {
+            self.is_playing = false;
+        }
+
+        let env_value = self.envelope.process();
+        sample * env_value
+    }
+}
+
+// 유니즌 보이스들의 초기 위상을 골고루 흩어 놓아 서로 위상 상쇄되지 않게 한다
+fn initial_unison_phases(voice_count: usize) -> Vec<f32> {
+    (0..voice_count)
+        .map(|i| i as f32 / voice_count as f32)
+        .collect()
+}
+
+// 노트 한 개를 재생하는 방식: 오실레이터 합성, FM 합성, 또는 사운드폰트 샘플 재생
+enum Voice {
+    Oscillator(Note, Vec<f32>, Envelope),
+    Fm(FmVoice),
+    Sample(SampleVoice),
+}
+
+impl Voice {
+    fn release(&mut self) {
+        match self {
+            Voice::Oscillator(_, _, envelope) => envelope.release(),
+            Voice::Fm(voice) => voice.release(),
+            Voice::Sample(voice) => voice.release(),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        match self {
+            Voice::Oscillator(_, _, envelope) => matches!(envelope.phase, EnvelopePhase::Idle),
+            Voice::Fm(voice) => voice.is_finished(),
+            Voice::Sample(voice) => !voice.is_playing,
+        }
+    }
+
+    // frequency_multiplier 는 피치 벤드와 비브라토를 합친 배율, amplitude_multiplier 는 트레몰로 배율이다
+    fn generate_sample(
+        &mut self,
+        frequency_multiplier: f32,
+        amplitude_multiplier: f32,
+        velocity_gain: f32,
+        sample_rate: f32,
+    ) -> f32 {
+        match self {
+            Voice::Oscillator(note, phases, envelope) => {
+                let env_value = envelope.process();
+                let mut note_clone = note.clone();
+                note_clone.frequency *= frequency_multiplier;
+                note_clone.amplitude = env_value * 0.2 * velocity_gain * amplitude_multiplier;
+                note_clone.generate_unison_sample(phases, sample_rate)
+            }
+            Voice::Fm(voice) => {
+                voice.generate_sample(frequency_multiplier, sample_rate) * velocity_gain * amplitude_multiplier
+            }
+            Voice::Sample(voice) => voice.generate_sample() * velocity_gain * amplitude_multiplier,
+        }
+    }
+}
+
 // 오디오 스트림 생성
+// 생성된 샘플을 담아두는 링 버퍼의 용량과 수위 기준점
+const SAMPLE_BUFFER_CAPACITY: usize = 8192;
+const SAMPLE_BUFFER_LOW_WATER_MARK: usize = 2048;
+const SAMPLE_BUFFER_HIGH_WATER_MARK: usize = 6144;
+// 한 번에 합성해서 밀어넣는 블록 크기
+const SYNTHESIS_BLOCK_SIZE: usize = 256;
+
+// 실시간 오디오 콜백에서 떼어낸 합성 작업을 전담하는 워커 스레드.
+// 키보드/MIDI 이벤트를 소비하고, 무거운 DSP 연산을 수행해 모노 샘플을 sample_producer 에 채워 넣는다.
+fn spawn_synthesis_worker(
+    sample_rate: f32,
+    mut event_consumer: impl Consumer<Item = SynthEvent> + Send + 'static,
+    mut sample_producer: impl Producer<Item = f32> + Send + 'static,
+    sound_font: Option<Arc<SoundFont>>,
+    fm_config: Option<FmConfig>,
+) {
+    thread::spawn(move || {
+        // 활성화된 노트를 MIDI 노트 번호로 추적 (velocity(0..1), 트리거 이후 경과 샘플 수)
+        let mut notes: HashMap<u8, (Voice, f32, usize)> = HashMap::new();
+
+        // 서스테인 페달 상태와, 페달이 눌려있는 동안 해제가 보류된 노트들
+        let mut sustain_on = false;
+        let mut sustained_off: HashSet<u8> = HashSet::new();
+
+        // 피치 벤드로 인한 전역 주파수 오프셋 (센트)
+        let mut pitch_bend_cents = 0.0f32;
+
+        // 비브라토/트레몰로용 공용 LFO (약 5Hz, 0.3 반음 비브라토, 30% 트레몰로, 0.4초 지연 후 페이드인)
+        let mut lfo = Lfo::new(5.0, 0.3, 0.3, 0.4);
+
+        // 오실레이터 선택 (기본적으로 사인파)
+        let oscillator_type = Oscillator::Sine;
+
+        loop {
+            // 키보드/MIDI 이벤트 처리
+            while let Some(event) = event_consumer.try_pop() {
+                match event {
+                    SynthEvent::NoteOn {
+                        note_number,
+                        velocity,
+                    } => {
+                        let velocity_gain = velocity as f32 / 127.0;
+
+                        // 사운드폰트가 로드되어 있으면 샘플 재생, FM 모드가 설정되어 있으면 FM 합성,
+                        // 둘 다 아니면 기본 오실레이터 합성을 사용한다
+                        let region = sound_font
+                            .as_ref()
+                            .and_then(|sf| sf.default_preset())
+                            .and_then(|preset| preset.region_for(note_number, velocity));
+
+                        if let Some(region) = region {
+                            let envelope = Envelope::new(0.01, 0.1, -3.0, 0.2, sample_rate);
+                            let voice =
+                                SampleVoice::new(Arc::new(region.clone()), note_number, sample_rate, envelope);
+                            notes.insert(note_number, (Voice::Sample(voice), velocity_gain, 0));
+                        } else if let Some(fm_config) = fm_config {
+                            let frequency = midi_note_to_frequency(note_number);
+                            let voice = FmVoice::with_default_operators(
+                                frequency,
+                                fm_config.algorithm,
+                                fm_config.feedback_strength,
+                                sample_rate,
+                            );
+                            notes.insert(note_number, (Voice::Fm(voice), velocity_gain, 0));
+                        } else {
+                            let frequency = midi_note_to_frequency(note_number);
+                            let mut note = Note::new(frequency, oscillator_type);
+                            note.is_playing = true;
+                            let envelope = Envelope::new(0.01, 0.1, -3.0, 0.2, sample_rate);
+                            notes.entry(note_number).or_insert((
+                                Voice::Oscillator(note, initial_unison_phases(UNISON_VOICE_COUNT), envelope),
+                                velocity_gain,
+                                0,
+                            ));
+                        }
+                        sustained_off.remove(&note_number);
+
+                        if let Some((voice, gain, samples_since_trigger)) = notes.get_mut(&note_number) {
+                            *gain = velocity_gain;
+                            *samples_since_trigger = 0;
+                            match voice {
+                                Voice::Oscillator(note, _, envelope) => {
+                                    note.is_playing = true;
+                                    envelope.trigger();
+                                }
+                                Voice::Fm(fm_voice) => fm_voice.trigger(),
+                                Voice::Sample(sample_voice) => sample_voice.trigger(),
+                            }
+                        }
+                    }
+                    SynthEvent::NoteOff { note_number } => {
+                        if sustain_on {
+                            // 페달이 눌려있으면 떼어도 실제 release 는 페달을 뗄 때까지 미룬다
+                            sustained_off.insert(note_number);
+                        } else if let Some((voice, _, _)) = notes.get_mut(&note_number) {
+                            voice.release();
+                        }
+                    }
+                    SynthEvent::PitchBend { cents } => {
+                        pitch_bend_cents = cents;
+                    }
+                    SynthEvent::Sustain { on } => {
+                        sustain_on = on;
+                        if !on {
+                            for note_number in sustained_off.drain() {
+                                if let Some((voice, _, _)) = notes.get_mut(&note_number) {
+                                    voice.release();
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            // 버퍼가 저수위 마크 아래로 떨어졌으면 고수위 마크까지 블록 단위로 채운다
+            if sample_producer.occupied_len() < SAMPLE_BUFFER_LOW_WATER_MARK {
+                while sample_producer.occupied_len() < SAMPLE_BUFFER_HIGH_WATER_MARK {
+                    let block_len = SYNTHESIS_BLOCK_SIZE.min(sample_producer.vacant_len());
+                    if block_len == 0 {
+                        break;
+                    }
+
+                    for _ in 0..block_len {
+                        let mut mix = 0.0;
+                        let lfo_value = lfo.advance(sample_rate);
+
+                        // 모든 활성화된 노트에 대해 샘플 생성
+                        for (_, (voice, velocity_gain, samples_since_trigger)) in notes.iter_mut() {
+                            let fade = lfo_fade_in(*samples_since_trigger, lfo.delay_seconds, sample_rate);
+                            let vibrato_multiplier =
+                                2f32.powf(lfo_value * fade * lfo.vibrato_depth_semitones / 12.0);
+                            let tremolo_multiplier =
+                                1.0 - lfo.tremolo_depth * fade * (0.5 - 0.5 * lfo_value);
+                            let frequency_multiplier =
+                                2f32.powf(pitch_bend_cents / 1200.0) * vibrato_multiplier;
+
+                            mix += voice.generate_sample(
+                                frequency_multiplier,
+                                tremolo_multiplier,
+                                *velocity_gain,
+                                sample_rate,
+                            );
+                            *samples_since_trigger += 1;
+                        }
+
+                        let _ = sample_producer.try_push(mix);
+
+                        // 더이상 사용하지 않는 노트 제거
+                        notes.retain(|_, (voice, _, _)| !voice.is_finished());
+                    }
+                }
+            }
+
+            // 버퍼가 충분히 채워져 있으면 잠깐 쉬어 워커가 코어를 독점하지 않게 한다
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+}
+
+// 오디오 스트림 생성: 콜백은 워커가 미리 채워둔 샘플을 복사만 하는 실시간 안전 경로다
 fn create_audio_stream(
     device: &cpal::Device,
     config: &cpal::SupportedStreamConfig,
-    mut consumer: impl Consumer<Item = (Keycode, bool)> + Send + 'static,
+    event_consumer: impl Consumer<Item = SynthEvent> + Send + 'static,
+    sound_font: Option<Arc<SoundFont>>,
+    fm_config: Option<FmConfig>,
 ) -> Result<Stream> {
     let sample_rate = config.sample_rate().0 as f32;
     let channels = config.channels() as usize;
 
-    // 노트 맵 생성
-    let key_frequency_map = create_key_frequency_map();
-
-    // 활성화된 노트 추적
-    let mut notes: HashMap<Keycode, (Note, f32, Envelope)> = HashMap::new();
+    let sample_ring = HeapRb::<f32>::new(SAMPLE_BUFFER_CAPACITY);
+    let (sample_producer, mut sample_consumer) = sample_ring.split();
 
-    // 오실레이터 선택 (기본적으로 사인파)
-    let oscillator_type = Oscillator::Sine;
+    spawn_synthesis_worker(sample_rate, event_consumer, sample_producer, sound_font, fm_config);
 
     let err_fn = |err| eprintln!("Audio Stream Error: {}", err);
 
@@ -222,52 +1068,26 @@ fn create_audio_stream(
         cpal::SampleFormat::F32 => device.build_output_stream(
             &config.config(),
             move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                // 키보드 이벤트 처리
-                while let Some((key, pressed)) = consumer.try_pop() {
-                    if let Some(&frequency) = key_frequency_map.get(&key) {
-                        if pressed {
-                            if !notes.contains_key(&key) {
-                                let mut note = Note::new(frequency, oscillator_type);
-                                note.is_playing = true;
-                                let envelope = Envelope::new(0.01, 0.1, 0.7, 0.2, sample_rate);
-                                notes.insert(key, (note, 0.0, envelope));
-                            }
-
-                            if let Some((note, _, envelope)) = notes.get_mut(&key) {
-                                note.is_playing = true;
-                                envelope.trigger();
-                            }
-                        } else {
-                            if let Some((note, _, envelope)) = notes.get_mut(&key) {
-                                envelope.release();
-                            }
-                        }
-                    }
-                }
-
-                // 오디오 샘플 생성
+                // 프레임 루프 안에서 매번 eprintln! 을 호출하면 콜백 한 번에 수백 번 로그를 찍을 수 있어
+                // 실시간 콜백에서 락/시스템 콜을 반복하게 된다. 언더런 여부만 추적해 콜백당 한 번만 로그한다.
+                let mut underrun = false;
                 for frame in data.chunks_mut(channels) {
-                    let mut mix = 0.0;
-
-                    // 모든 활성화된 노트에 대해 샘플 생성
-                    for (_, (note, phase, envelope)) in notes.iter_mut() {
-                        let env_value = envelope.process();
-                        let mut note_clone = note.clone();
-                        note_clone.amplitude = env_value * 0.2;
-                        let sample = note_clone.generate_sample(phase, sample_rate);
-                        mix += sample;
-                    }
+                    let sample = match sample_consumer.try_pop() {
+                        Some(sample) => sample,
+                        None => {
+                            underrun = true;
+                            0.0
+                        }
+                    };
 
                     // 채널 수에 따라 모든 채널에 같은 값 할당
                     for channel in frame.iter_mut() {
-                        *channel = Sample::to_sample(mix);
+                        *channel = Sample::to_sample(sample);
                     }
+                }
 
-                    // 더이상 사용하지 않는 노트 제거
-                    notes.retain(|_, (_, _, envelope)| match envelope.phase {
-                        EnvelopePhase::Idle => false,
-                        _ => true,
-                    });
+                if underrun {
+                    eprintln!("Sample buffer underrun, outputting silence");
                 }
             },
             err_fn,
@@ -293,15 +1113,63 @@ fn main() -> Result<()> {
     println!("Default output device: {:?}", device.name());
     println!("Default output config: {:?}", config);
 
-    // 키보드 이벤트 처리를 위한 링 버퍼
-    let ring_buffer = HeapRb::<(Keycode, bool)>::new(1024);
-    let (mut producer, consumer) = ring_buffer.split();
+    // 키보드/MIDI 이벤트 처리를 위한 공용 링 버퍼
+    let ring_buffer = HeapRb::<SynthEvent>::new(1024);
+    let (producer, consumer) = ring_buffer.split();
+    let producer = Arc::new(Mutex::new(producer));
+
+    // DINGDANGDONG_SOUNDFONT 환경 변수가 지정되어 있으면 .sf2 파일을 로드해 샘플 재생 모드로 전환한다
+    let sound_font = match std::env::var("DINGDANGDONG_SOUNDFONT") {
+        Ok(path) => match SoundFont::load(&path) {
+            Ok(sound_font) => {
+                println!("Loaded SoundFont: {}", path);
+                Some(Arc::new(sound_font))
+            }
+            Err(err) => {
+                eprintln!("Failed to load SoundFont {}: {}", path, err);
+                None
+            }
+        },
+        Err(_) => None,
+    };
+
+    // DINGDANGDONG_FM_ALGORITHM 환경 변수가 지정되어 있으면 FM 합성 모드로 전환한다 (0~7, 사운드폰트보다 우선순위 낮음).
+    // DINGDANGDONG_FM_FEEDBACK (0~7, 기본 0)으로 오퍼레이터 1의 셀프 피드백 강도를 조절할 수 있다
+    let fm_config = std::env::var("DINGDANGDONG_FM_ALGORITHM")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .map(|algorithm| {
+            let feedback_strength = std::env::var("DINGDANGDONG_FM_FEEDBACK")
+                .ok()
+                .and_then(|value| value.parse::<u8>().ok())
+                .unwrap_or(0);
+            FmConfig {
+                algorithm: algorithm.min(7),
+                feedback_strength: feedback_strength.min(7),
+            }
+        });
+    if let Some(fm_config) = fm_config {
+        println!(
+            "FM synthesis mode: algorithm {}, feedback {}",
+            fm_config.algorithm, fm_config.feedback_strength
+        );
+    }
 
     // 오디오 스트림 생성 및 시작
-    let stream = create_audio_stream(&device, &config, consumer)?;
+    let stream = create_audio_stream(&device, &config, consumer, sound_font, fm_config)?;
     stream.play()?;
 
+    // MIDI 입력은 선택 사항: 연결된 장치가 없으면 키보드만으로 계속 진행한다
+    let _midi_connection = match open_midi_input(Arc::clone(&producer)) {
+        Ok(connection) => connection,
+        Err(err) => {
+            eprintln!("MIDI input unavailable: {}", err);
+            None
+        }
+    };
+
     // 키보드 상태 모니터링
+    let key_note_map = create_key_note_map();
     let device_state = DeviceState::new();
     let mut previous_keys = Vec::new();
 
@@ -314,13 +1182,28 @@ fn main() -> Result<()> {
 
         for key in &keys {
             if !previous_keys.contains(key) {
-                producer.try_push((*key, true)).unwrap();
+                if let Some(&note_number) = key_note_map.get(key) {
+                    producer
+                        .lock()
+                        .unwrap()
+                        .try_push(SynthEvent::NoteOn {
+                            note_number,
+                            velocity: KEYBOARD_VELOCITY,
+                        })
+                        .unwrap();
+                }
             }
         }
 
         for key in &previous_keys {
             if !keys.contains(key) {
-                producer.try_push((*key, false)).unwrap()
+                if let Some(&note_number) = key_note_map.get(key) {
+                    producer
+                        .lock()
+                        .unwrap()
+                        .try_push(SynthEvent::NoteOff { note_number })
+                        .unwrap();
+                }
             }
         }
 
@@ -334,3 +1217,133 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instrument_zone(sample_id: u16, key_range: Option<(u8, u8)>, vel_range: Option<(u8, u8)>) -> sf2::Zone {
+        let mut gen_list = vec![sf2::GeneratorType::SampleID(sample_id)];
+        if let Some((low, high)) = key_range {
+            gen_list.push(sf2::GeneratorType::KeyRange(sf2::Range { low, high }));
+        }
+        if let Some((low, high)) = vel_range {
+            gen_list.push(sf2::GeneratorType::VelRange(sf2::Range { low, high }));
+        }
+        sf2::Zone { gen_list }
+    }
+
+    #[test]
+    fn sample_regions_from_preset_zone_resolves_sample_id_through_instrument() {
+        // SampleID 는 프리셋 존이 아니라, 프리셋 존이 가리키는 악기 자신의 존에 있다
+        let sound_font = sf2::SoundFont2 {
+            sample_headers: vec![sf2::SampleHeader {
+                start: 0,
+                end: 4,
+                startloop: 1,
+                endloop: 3,
+                sample_rate: 44100,
+                origpitch: 60,
+            }],
+            sample_data: vec![0, 1000, 2000, 3000],
+            presets: Vec::new(),
+            instruments: vec![sf2::Instrument {
+                header: sf2::InstrumentHeader {
+                    name: "Piano".to_string(),
+                },
+                zones: vec![instrument_zone(0, Some((60, 72)), None)],
+            }],
+        };
+        let preset_zone = sf2::Zone {
+            gen_list: vec![sf2::GeneratorType::Instrument(0)],
+        };
+
+        let regions = sample_regions_from_preset_zone(&sound_font, &preset_zone);
+
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].loop_start, 1);
+        assert_eq!(regions[0].loop_end, 3);
+        assert!(regions[0].matches(65, 100));
+        assert!(!regions[0].matches(80, 100));
+    }
+
+    #[test]
+    fn instrument_zone_inherits_velocity_range_from_preset_zone() {
+        // 악기 존에 VelRange 가 없으면 프리셋 존의 VelRange 를 물려받는다 (제너레이터 상속 규칙)
+        let sound_font = sf2::SoundFont2 {
+            sample_headers: vec![sf2::SampleHeader {
+                start: 0,
+                end: 2,
+                startloop: 0,
+                endloop: 0,
+                sample_rate: 44100,
+                origpitch: 60,
+            }],
+            sample_data: vec![0, 1000],
+            presets: Vec::new(),
+            instruments: vec![sf2::Instrument {
+                header: sf2::InstrumentHeader {
+                    name: "Strings".to_string(),
+                },
+                zones: vec![instrument_zone(0, None, None)],
+            }],
+        };
+        let preset_zone = sf2::Zone {
+            gen_list: vec![
+                sf2::GeneratorType::Instrument(0),
+                sf2::GeneratorType::VelRange(sf2::Range { low: 80, high: 127 }),
+            ],
+        };
+
+        let regions = sample_regions_from_preset_zone(&sound_font, &preset_zone);
+
+        assert_eq!(regions.len(), 1);
+        assert!(regions[0].matches(60, 100));
+        assert!(!regions[0].matches(60, 50));
+    }
+
+    #[test]
+    fn preset_zone_without_instrument_generator_yields_no_regions() {
+        let sound_font = sf2::SoundFont2 {
+            sample_headers: Vec::new(),
+            sample_data: Vec::new(),
+            presets: Vec::new(),
+            instruments: Vec::new(),
+        };
+        let preset_zone = sf2::Zone { gen_list: Vec::new() };
+
+        assert!(sample_regions_from_preset_zone(&sound_font, &preset_zone).is_empty());
+    }
+
+    #[test]
+    fn sample_voice_generate_sample_linearly_interpolates_and_stops_at_end() {
+        let region = Arc::new(SampleRegion {
+            samples: Arc::new(vec![0, 10000, 20000]),
+            sample_rate: 44100,
+            root_key: 48, // note_number - root_key == -12 반음 => playback_rate 0.5
+            low_key: 0,
+            high_key: 127,
+            low_velocity: 0,
+            high_velocity: 127,
+            loop_start: 0,
+            loop_end: 0,
+        });
+        let envelope = Envelope::new(0.0, 0.0, 0.0, 0.0, 44100.0);
+        let mut voice = SampleVoice::new(region, 36, 44100.0, envelope);
+        voice.trigger();
+
+        let first = voice.generate_sample();
+        assert!((first - 0.0).abs() < 1e-4);
+
+        // playback_position 이 0.5 가 된 상태에서 샘플[0]과 샘플[1] 사이를 절반 보간한다
+        let second = voice.generate_sample();
+        let expected = (10000.0 / i16::MAX as f32) * 0.5;
+        assert!((second - expected).abs() < 1e-4);
+
+        assert!(voice.is_playing);
+        for _ in 0..10 {
+            voice.generate_sample();
+        }
+        assert!(!voice.is_playing);
+    }
+}